@@ -64,6 +64,9 @@ async fn main() {
         .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
         .boxed();
 
+    let mut rendezvous = Rendezvous::new();
+    rendezvous.set_identity(identity.clone());
+
     let local_peer_id = PeerId::from(identity.public());
     let mut swarm = Swarm::new(
         transport,
@@ -72,7 +75,7 @@ async fn main() {
                 "rendezvous-example/1.0.0".to_string(),
                 identity.public(),
             )),
-            rendezvous: Rendezvous::new(identity, 10000),
+            rendezvous,
         },
         local_peer_id,
     );
@@ -89,12 +92,18 @@ async fn main() {
     loop {
         let event = swarm.next().await;
         match event {
-            Some(SwarmEvent::Behaviour(MyEvent::Identify(IdentifyEvent::Received { .. }))) => {
+            Some(SwarmEvent::Behaviour(MyEvent::Identify(IdentifyEvent::Received {
+                info,
+                ..
+            }))) => {
+                swarm
+                    .behaviour_mut()
+                    .rendezvous
+                    .set_external_addresses(vec![info.observed_addr]);
                 swarm
                     .behaviour_mut()
                     .rendezvous
-                    .register("rendezvous".to_string(), server_peer_id, None)
-                    .unwrap();
+                    .register("rendezvous".to_string(), server_peer_id, None);
             }
             Some(SwarmEvent::Behaviour(MyEvent::Rendezvous(event))) => {
                 println!("registered event: {:?}", event);
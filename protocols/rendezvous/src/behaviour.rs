@@ -1,17 +1,100 @@
 use crate::codec::{ErrorCode, Message, Registration};
 use crate::handler::{Input, RendezvousHandler};
 use libp2p_core::connection::ConnectionId;
-use libp2p_core::{AuthenticatedPeerRecord, Multiaddr, PeerId};
+use libp2p_core::identity::Keypair;
+use libp2p_core::{AuthenticatedPeerRecord, ConnectedPoint, Multiaddr, PeerId, PeerRecord};
 use libp2p_swarm::{
-    NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, PollParameters, ProtocolsHandler,
+    DialPeerCondition, NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, PollParameters,
+    ProtocolsHandler,
 };
-use log::debug;
-use std::collections::{HashMap, HashSet, VecDeque};
+use log::{debug, error};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use wasm_timer::Delay;
+
+/// The maximum TTL, in seconds, a registration may request. The rendezvous spec allows a server
+/// to cap registrations so that a single peer cannot pin itself (or anyone else) into the
+/// namespace forever.
+const DEFAULT_MAX_TTL: i64 = 72 * 60 * 60;
+
+/// The TTL, in seconds, we request when none is given explicitly. Mirrors the rendezvous spec's
+/// own default.
+const DEFAULT_TTL: i64 = 7200;
+
+/// The state of our connection to a rendezvous node we have registered (or are registering)
+/// with. Re-registration is only ever attempted while `Connected`; a `Disconnected` node has its
+/// registrations re-sent as soon as it reaches `Connected` again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionState {
+    Dialed,
+    Connected,
+    Disconnected,
+}
+
+/// Bookkeeping for a registration this node made with a rendezvous node, kept so
+/// [`Rendezvous::poll`] can refresh it before the server drops it.
+struct OutgoingRegistration {
+    record: AuthenticatedPeerRecord,
+    ttl: i64,
+    refresh_at: Instant,
+}
 
 pub struct Rendezvous {
     events: VecDeque<NetworkBehaviourAction<Input, Event>>,
-    registrations: HashMap<String, HashSet<Registration>>,
+    registrations: HashMap<String, HashMap<PeerId, Registration>>,
+    /// The expiry instant currently in effect for each `(namespace, peer)` registration, used to tell a
+    /// live heap entry apart from one made stale by a re-registration.
+    expiries: HashMap<(String, PeerId), Instant>,
+    /// Per-namespace counter, bumped on every registration; the value a registration was made at
+    /// becomes its sequence number, used to build and validate discovery cookies.
+    namespace_seq: HashMap<String, u64>,
+    /// The sequence number each live registration was made at. Compared against a discovery
+    /// cookie to tell a caller only about registrations it hasn't already seen.
+    registration_seq: HashMap<(String, PeerId), u64>,
+    /// Min-heap of upcoming expiries, so `poll` only has to look at the soonest one.
+    expiry_queue: BinaryHeap<Reverse<(Instant, String, PeerId)>>,
+    /// Armed for the soonest known expiry; re-armed every time it fires or a new, sooner
+    /// registration comes in.
+    expiry_timer: Option<Delay>,
+    max_ttl: i64,
+    /// Whether this node keeps its own registrations alive by re-registering before they expire.
+    auto_refresh: bool,
+    /// The `(namespace, rendezvous_node)` registrations we made ourselves, kept so they can be
+    /// refreshed. Only populated when `auto_refresh` is enabled.
+    outgoing_registrations: HashMap<(String, PeerId), OutgoingRegistration>,
+    /// Armed for the soonest `refresh_at` deadline in `outgoing_registrations`.
+    refresh_timer: Option<Delay>,
+    connection_states: HashMap<PeerId, ConnectionState>,
+    /// Live connections to each peer, oldest first. [`Rendezvous::send_register_request`] always
+    /// targets the oldest one (falling back to `NotifyHandler::Any` if we don't have one yet), so
+    /// a peer's `RegisterRequest` responses keep landing on the `pending_registrations` queue
+    /// they were enqueued against even if we end up with more than one connection to it.
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    /// Namespaces of our own `RegisterRequest`s sent on a given connection, oldest first. The
+    /// wire response doesn't carry the namespace it answers, so we match it up by assuming
+    /// requests sent on the same connection are answered in the order we sent them.
+    pending_registrations: HashMap<(PeerId, ConnectionId), VecDeque<String>>,
+    /// Namespaces of `RegisterRequest`s sent to a node we aren't connected to yet (e.g. right
+    /// after [`Rendezvous::add_rendezvous_node`]), oldest first. Drained into
+    /// `pending_registrations` by `inject_connection_established` once we know which connection
+    /// the request actually rides on.
+    pending_registrations_without_connection: HashMap<PeerId, VecDeque<String>>,
+    /// Our keypair, needed to sign the [`PeerRecord`] built from `external_addresses` when
+    /// [`Rendezvous::register`] isn't given a record explicitly.
+    identity: Option<Keypair>,
+    /// The addresses we advertise when auto-building a peer record, kept up to date from
+    /// [`Rendezvous::set_external_addresses`] and from `PollParameters::external_addresses`
+    /// (e.g. as populated by `Identify`/AutoNAT) on every `poll`.
+    external_addresses: Vec<Multiaddr>,
+    /// The rendezvous nodes this instance is configured to use, e.g. via
+    /// [`Rendezvous::add_rendezvous_node`]. Used to dial nodes we aren't connected to yet
+    /// (`addresses_of_peer`) and to fan registrations out to every configured node
+    /// ([`Rendezvous::register_with_all`]).
+    rendezvous_nodes: HashMap<PeerId, Multiaddr>,
 }
 
 impl Rendezvous {
@@ -19,28 +102,192 @@ impl Rendezvous {
         Self {
             events: Default::default(),
             registrations: Default::default(),
+            expiries: Default::default(),
+            namespace_seq: Default::default(),
+            registration_seq: Default::default(),
+            expiry_queue: BinaryHeap::new(),
+            expiry_timer: None,
+            max_ttl: DEFAULT_MAX_TTL,
+            auto_refresh: false,
+            outgoing_registrations: Default::default(),
+            refresh_timer: None,
+            connection_states: Default::default(),
+            connections: Default::default(),
+            pending_registrations: Default::default(),
+            pending_registrations_without_connection: Default::default(),
+            identity: None,
+            external_addresses: Vec::new(),
+            rendezvous_nodes: Default::default(),
+        }
+    }
+
+    /// Same as [`Rendezvous::new`] but with a custom cap on the TTL a registration may request.
+    pub fn with_max_ttl(max_ttl: i64) -> Self {
+        Self {
+            max_ttl,
+            ..Self::new()
+        }
+    }
+
+    /// Whether a requested TTL exceeds the cap this instance enforces on registrations.
+    fn exceeds_max_ttl(&self, ttl: i64) -> bool {
+        ttl > self.max_ttl
+    }
+
+    /// Opt into automatically re-registering with every rendezvous node before our TTL with it
+    /// lapses, so a long-running node stays discoverable without the application re-calling
+    /// [`Rendezvous::register`] itself.
+    pub fn enable_auto_refresh(&mut self) {
+        self.auto_refresh = true;
+    }
+
+    /// Sets the keypair used to sign the peer record this node registers with when
+    /// [`Rendezvous::register`] isn't given a record explicitly.
+    pub fn set_identity(&mut self, identity: Keypair) {
+        self.identity = Some(identity);
+    }
+
+    /// Sets the addresses this node is externally reachable at, e.g. as confirmed by `Identify`
+    /// or AutoNAT, so they can be signed into a peer record automatically. Also kept up to date
+    /// from `PollParameters::external_addresses` on every `poll`.
+    pub fn set_external_addresses(&mut self, addresses: Vec<Multiaddr>) {
+        self.external_addresses = addresses;
+    }
+
+    /// Adds `peer_id` to the set of rendezvous nodes this instance manages, dialing it via
+    /// `address` if we aren't connected to it yet. Configured nodes are the ones
+    /// [`Rendezvous::register_with_all`] fans registrations out to.
+    pub fn add_rendezvous_node(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.rendezvous_nodes.insert(peer_id, address);
+
+        if self.connection_states.contains_key(&peer_id) {
+            return;
         }
+
+        self.connection_states
+            .insert(peer_id, ConnectionState::Dialed);
+        self.events.push_back(NetworkBehaviourAction::DialPeer {
+            peer_id,
+            condition: DialPeerCondition::Disconnected,
+        });
     }
 
+    /// Registers `record` (or an auto-built one, see [`Rendezvous::register`]) with every
+    /// rendezvous node added via [`Rendezvous::add_rendezvous_node`]. This call does not dial;
+    /// nodes that aren't connected yet were already dialed by
+    /// [`Rendezvous::add_rendezvous_node`]. This is the redundant-rendezvous-point equivalent of calling
+    /// [`Rendezvous::register`] once per node.
+    pub fn register_with_all(&mut self, ns: String, record: Option<AuthenticatedPeerRecord>) {
+        let rendezvous_nodes: Vec<PeerId> = self.rendezvous_nodes.keys().copied().collect();
+
+        for rendezvous_node in rendezvous_nodes {
+            self.register(ns.clone(), rendezvous_node, record.clone());
+        }
+    }
+
+    /// Registers `record` (or, if `None`, a record built from this node's current external
+    /// addresses; see [`Rendezvous::set_identity`] and [`Rendezvous::set_external_addresses`])
+    /// with `rendezvous_node` under `ns`.
     pub fn register(
         &mut self,
         ns: String,
         rendezvous_node: PeerId,
+        record: Option<AuthenticatedPeerRecord>,
+    ) {
+        let record = match record.or_else(|| self.build_record()) {
+            Some(record) => record,
+            None => {
+                error!(
+                    "cannot register in namespace {} without a peer record; \
+                     call `set_identity` and `set_external_addresses` first",
+                    ns
+                );
+                return;
+            }
+        };
+
+        if self.auto_refresh {
+            let ttl = DEFAULT_TTL;
+            self.outgoing_registrations.insert(
+                (ns.clone(), rendezvous_node),
+                OutgoingRegistration {
+                    record: record.clone(),
+                    ttl,
+                    refresh_at: Instant::now() + Duration::from_secs((ttl / 2) as u64),
+                },
+            );
+        }
+
+        self.send_register_request(ns, rendezvous_node, None, record);
+    }
+
+    /// Signs a fresh [`AuthenticatedPeerRecord`] from `external_addresses`, if we have an
+    /// identity and at least one address to advertise.
+    fn build_record(&self) -> Option<AuthenticatedPeerRecord> {
+        let identity = self.identity.as_ref()?;
+
+        if self.external_addresses.is_empty() {
+            return None;
+        }
+
+        let record = PeerRecord::new(identity, self.external_addresses.clone()).ok()?;
+
+        AuthenticatedPeerRecord::from_record(identity, record).ok()
+    }
+
+    /// Sends a `RegisterRequest` and remembers its namespace, keyed by the connection it went
+    /// out on, so the (namespace-less) response can be matched back up to it in
+    /// [`NetworkBehaviour::inject_event`] even if we have more than one connection to
+    /// `rendezvous_node`.
+    fn send_register_request(
+        &mut self,
+        ns: String,
+        rendezvous_node: PeerId,
+        ttl: Option<i64>,
         record: AuthenticatedPeerRecord,
     ) {
+        let connection = self
+            .connections
+            .get(&rendezvous_node)
+            .and_then(|connections| connections.first())
+            .copied();
+
+        let handler = match connection {
+            Some(connection) => {
+                self.pending_registrations
+                    .entry((rendezvous_node, connection))
+                    .or_insert_with(VecDeque::new)
+                    .push_back(ns.clone());
+                NotifyHandler::One(connection)
+            }
+            // We don't know which connection this will go out on yet (e.g. `register_with_all`
+            // right after `add_rendezvous_node`, before it has even dialed). Queue the namespace
+            // here instead of dropping the correlation; `inject_connection_established` moves it
+            // into `pending_registrations` once a connection exists to key it against.
+            None => {
+                self.pending_registrations_without_connection
+                    .entry(rendezvous_node)
+                    .or_insert_with(VecDeque::new)
+                    .push_back(ns.clone());
+                NotifyHandler::Any
+            }
+        };
+
         self.events
             .push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id: rendezvous_node,
                 event: Input::RegisterRequest {
                     namespace: ns,
-                    ttl: None,
+                    ttl,
                     record,
                 },
-                handler: NotifyHandler::Any,
+                handler,
             });
     }
 
     pub fn unregister(&mut self, ns: String, rendezvous_node: PeerId) {
+        self.outgoing_registrations.remove(&(ns.clone(), rendezvous_node));
+
         self.events
             .push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id: rendezvous_node,
@@ -48,21 +295,77 @@ impl Rendezvous {
                 handler: NotifyHandler::Any,
             });
     }
-    pub fn discover(&mut self, ns: Option<String>, rendezvous_node: PeerId) {
+    /// Asks `rendezvous_node` for registrations, optionally scoped to a namespace.
+    ///
+    /// `cookie` should be `None` on the first call and thereafter set to the cookie returned in
+    /// the previous [`Event::Discovered`] for the same namespace, so only registrations made
+    /// since then are returned. `limit` caps how many registrations come back in one response.
+    pub fn discover(
+        &mut self,
+        ns: Option<String>,
+        cookie: Option<Vec<u8>>,
+        limit: Option<i64>,
+        rendezvous_node: PeerId,
+    ) {
         self.events
             .push_back(NetworkBehaviourAction::NotifyHandler {
                 peer_id: rendezvous_node,
-                event: Input::DiscoverRequest { namespace: ns },
+                event: Input::DiscoverRequest {
+                    namespace: ns,
+                    cookie,
+                    limit,
+                },
                 handler: NotifyHandler::Any,
             });
     }
 }
 
+/// Encodes a discovery cookie as `(namespace, sequence number)`. The layout is internal to the
+/// server and only needs to round-trip through [`decode_cookie`]: a 4-byte big-endian length,
+/// the namespace bytes, then an 8-byte big-endian sequence number.
+fn encode_cookie(ns: &str, seq: u64) -> Vec<u8> {
+    let mut cookie = Vec::with_capacity(4 + ns.len() + 8);
+    cookie.extend_from_slice(&(ns.len() as u32).to_be_bytes());
+    cookie.extend_from_slice(ns.as_bytes());
+    cookie.extend_from_slice(&seq.to_be_bytes());
+    cookie
+}
+
+fn decode_cookie(cookie: &[u8]) -> Option<(String, u64)> {
+    let ns_len = u32::from_be_bytes(cookie.get(0..4)?.try_into().ok()?) as usize;
+    let ns_end = 4 + ns_len;
+    let ns = String::from_utf8(cookie.get(4..ns_end)?.to_vec()).ok()?;
+    let seq = u64::from_be_bytes(cookie.get(ns_end..ns_end + 8)?.try_into().ok()?);
+    Some((ns, seq))
+}
+
+/// Sorts `candidates` by sequence number and truncates to `limit`, returning the trimmed items
+/// alongside the sequence number to encode into the next discovery cookie: the highest one
+/// actually returned, so a later call with that cookie resumes exactly where this response left
+/// off. Falls back to `since` when nothing matched, so an empty page doesn't rewind the cookie.
+///
+/// Deliberately generic over the payload so the pagination itself can be unit-tested without a
+/// [`Registration`].
+fn paginate<T>(mut candidates: Vec<(u64, T)>, limit: Option<usize>, since: u64) -> (Vec<T>, u64) {
+    candidates.sort_by_key(|(seq, _)| *seq);
+
+    if let Some(limit) = limit {
+        candidates.truncate(limit);
+    }
+
+    let max_seq = candidates.last().map(|(seq, _)| *seq).unwrap_or(since);
+
+    (candidates.into_iter().map(|(_, item)| item).collect(), max_seq)
+}
+
 #[derive(Debug)]
 pub enum Event {
     Discovered {
         rendezvous_node: PeerId,
-        ns: Vec<Registration>,
+        registrations: Vec<Registration>,
+        /// Pass this back into the next [`Rendezvous::discover`] call for this namespace to
+        /// only receive registrations made since this response.
+        cookie: Option<Vec<u8>>,
     },
     FailedToDiscover {
         rendezvous_node: PeerId,
@@ -90,6 +393,10 @@ pub enum Event {
         peer_id: PeerId,
         ns: String,
     },
+    RegistrationExpired {
+        peer_id: PeerId,
+        ns: String,
+    },
 }
 
 impl NetworkBehaviour for Rendezvous {
@@ -100,34 +407,136 @@ impl NetworkBehaviour for Rendezvous {
         RendezvousHandler::new()
     }
 
-    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
-        Vec::new()
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.rendezvous_nodes
+            .get(peer_id)
+            .cloned()
+            .into_iter()
+            .collect()
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        _endpoint: &ConnectedPoint,
+    ) {
+        let is_first_connection = !self.connections.contains_key(peer_id);
+
+        self.connections
+            .entry(*peer_id)
+            .or_insert_with(Vec::new)
+            .push(*connection_id);
+
+        if is_first_connection {
+            if let Some(backlog) = self.pending_registrations_without_connection.remove(peer_id) {
+                self.pending_registrations
+                    .insert((*peer_id, *connection_id), backlog);
+            }
+        }
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer_id: &PeerId,
+        connection_id: &ConnectionId,
+        _endpoint: &ConnectedPoint,
+    ) {
+        if let Some(connections) = self.connections.get_mut(peer_id) {
+            connections.retain(|id| id != connection_id);
+            if connections.is_empty() {
+                self.connections.remove(peer_id);
+            }
+        }
     }
 
     fn inject_connected(&mut self, peer_id: &PeerId) {
         debug!("New peer connected: {}", peer_id);
-        // Dont need to do anything here?
+
+        let was_disconnected = self.connection_states.insert(*peer_id, ConnectionState::Connected)
+            == Some(ConnectionState::Disconnected);
+
+        if self.auto_refresh && was_disconnected {
+            self.reregister_all(peer_id);
+        }
     }
 
     fn inject_disconnected(&mut self, peer_id: &PeerId) {
         debug!("Peer disconnected: {}", peer_id);
-        // Don't need to do anything?
+
+        if self.is_managed_rendezvous_node(peer_id) {
+            self.connection_states
+                .insert(*peer_id, ConnectionState::Disconnected);
+
+            // Redial straight away so a registration to this node isn't permanently lost the
+            // moment it drops its connection; this is what makes a managed set of rendezvous
+            // nodes actually redundant. The state stays `Disconnected` (rather than `Dialed`) so
+            // `inject_connected` still recognises the eventual reconnect and re-registers. If this
+            // dial itself fails, `inject_dial_failure` queues the next attempt.
+            self.events.push_back(NetworkBehaviourAction::DialPeer {
+                peer_id: *peer_id,
+                condition: DialPeerCondition::Disconnected,
+            });
+        } else {
+            self.connection_states.remove(peer_id);
+        }
+    }
+
+    fn inject_dial_failure(&mut self, peer_id: &PeerId) {
+        debug!("Failed to dial {}", peer_id);
+
+        // A managed node is worth retrying indefinitely: it's how the managed set stays
+        // redundant when a node is offline for a while rather than just disconnected.
+        if self.is_managed_rendezvous_node(peer_id) {
+            self.events.push_back(NetworkBehaviourAction::DialPeer {
+                peer_id: *peer_id,
+                condition: DialPeerCondition::Disconnected,
+            });
+        }
     }
 
     fn inject_event(
         &mut self,
         peer_id: PeerId,
-        _connection: ConnectionId,
+        connection: ConnectionId,
         event: crate::handler::HandlerEvent,
     ) {
         match event.0 {
             Message::Register(new_reggo) => {
                 let ttl = new_reggo.effective_ttl();
 
+                if self.exceeds_max_ttl(ttl) {
+                    self.events
+                        .push_back(NetworkBehaviourAction::NotifyHandler {
+                            peer_id,
+                            handler: NotifyHandler::Any,
+                            event: Input::RegisterResponse {
+                                ttl,
+                                message: Message::FailedToRegister {
+                                    error: ErrorCode::InvalidTtl,
+                                },
+                            },
+                        });
+                    return;
+                }
+
+                let ns = new_reggo.namespace.clone();
+                let expires_at = Instant::now() + Duration::from_secs(ttl as u64);
+
                 self.registrations
-                    .entry(new_reggo.namespace)
-                    .or_insert_with(|| HashSet::new())
-                    .insert(new_reggo.clone());
+                    .entry(ns.clone())
+                    .or_insert_with(HashMap::new)
+                    .insert(peer_id, new_reggo.clone());
+
+                let seq = {
+                    let counter = self.namespace_seq.entry(ns.clone()).or_insert(0);
+                    *counter += 1;
+                    *counter
+                };
+                self.registration_seq.insert((ns.clone(), peer_id), seq);
+
+                self.expiries.insert((ns.clone(), peer_id), expires_at);
+                self.expiry_queue.push(Reverse((expires_at, ns, peer_id)));
 
                 self.events
                     .push_back(NetworkBehaviourAction::NotifyHandler {
@@ -140,85 +549,114 @@ impl NetworkBehaviour for Rendezvous {
                     })
             }
             Message::SuccessfullyRegistered { ttl } => {
-                // where to get namespace from?
+                let ns = self.next_pending_registration_namespace(&peer_id, connection);
+
+                // The TTL we asked for is only a request; the server's response carries the one
+                // it actually granted. Reconcile `outgoing_registrations` with it so the refresh
+                // timer is armed against the TTL we actually have, not the one we hoped for.
+                if let Some(outgoing) = self
+                    .outgoing_registrations
+                    .get_mut(&(ns.clone(), peer_id))
+                {
+                    outgoing.ttl = ttl;
+                    outgoing.refresh_at = Instant::now() + Duration::from_secs((ttl / 2) as u64);
+                }
+
                 self.events.push_back(NetworkBehaviourAction::GenerateEvent(
                     Event::RegisteredWithRendezvousNode {
                         rendezvous_node: peer_id,
-                        ns: "".to_string(),
+                        ns,
                         ttl,
                     },
                 ))
             }
             Message::FailedToRegister { error } => {
+                let ns = self.next_pending_registration_namespace(&peer_id, connection);
+
                 self.events.push_back(NetworkBehaviourAction::GenerateEvent(
                     Event::FailedToRegisterWithRendezvousNode {
                         rendezvous_node: peer_id,
-                        // todo: need to get the namespace somehow? The handler will probably have to remember
-                        // the request this message is a response to as the wire message does not contain this info
-                        ns: "".to_string(),
+                        ns,
                         err_code: error,
                     },
                 ))
             }
             Message::Unregister { namespace } => {
                 if let Some(registrations) = self.registrations.get_mut(&namespace) {
-                    if registrations.contains(&peer_id) {
-                        registrations.remove(&peer_id);
-                    }
+                    registrations.remove(&peer_id);
                 }
+                // Leave the now-stale entry in `expiry_queue`; `poll_expiries` discards it
+                // once it surfaces because `expiries` no longer agrees on its expiry instant.
+                self.expiries.remove(&(namespace.clone(), peer_id));
+                self.registration_seq.remove(&(namespace, peer_id));
                 // todo: maybe send a unregister response to the remote?
             }
-            Message::Discover { namespace } => {
-                if let Some(ns) = namespace {
-                    if let Some(peers) = self.registrations.get_mut(&ns) {
-                        self.events
-                            .push_back(NetworkBehaviourAction::NotifyHandler {
-                                peer_id,
-                                handler: NotifyHandler::Any,
-                                event: Input::DiscoverResponse {
-                                    registrations: peers.iter().map(|r| r.clone()).collect(),
-                                },
-                            });
+            Message::Discover {
+                namespace,
+                cookie,
+                limit,
+            } => {
+                let limit = limit.filter(|&limit| limit >= 0).map(|limit| limit as usize);
+
+                let (registrations, cookie) = match namespace {
+                    Some(ns) => {
+                        let since = cookie
+                            .as_deref()
+                            .and_then(decode_cookie)
+                            .filter(|(cookie_ns, _)| cookie_ns == &ns)
+                            .map(|(_, seq)| seq)
+                            .unwrap_or(0);
+
+                        let matching: Vec<(u64, Registration)> = self
+                            .registrations
+                            .get(&ns)
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|(peer, registration)| {
+                                let seq = *self.registration_seq.get(&(ns.clone(), *peer))?;
+                                (seq > since).then(|| (seq, registration.clone()))
+                            })
+                            .collect();
+
+                        let (registrations, max_seq) = paginate(matching, limit, since);
+
+                        (registrations, Some(encode_cookie(&ns, max_seq)))
                     }
-                } else {
-                    let discovered = self
-                        .registrations
-                        .iter()
-                        .map(|(ns, registrations)| {
-                            registrations
-                                .iter()
-                                .map(|registration| registration.clone())
-                                .collect::<Vec<Registration>>()
-                                .into_iter()
-                        })
-                        .flatten()
-                        .collect::<Vec<Registration>>();
+                    None => {
+                        let mut discovered: Vec<Registration> = self
+                            .registrations
+                            .values()
+                            .flat_map(|registrations| registrations.values().cloned())
+                            .collect();
+
+                        if let Some(limit) = limit {
+                            discovered.truncate(limit);
+                        }
+
+                        (discovered, None)
+                    }
+                };
 
-                    self.events
-                        .push_back(NetworkBehaviourAction::NotifyHandler {
-                            peer_id,
-                            handler: NotifyHandler::Any,
-                            event: Input::DiscoverResponse {
-                                registrations: discovered,
-                            },
-                        });
-                }
                 self.events
                     .push_back(NetworkBehaviourAction::NotifyHandler {
                         peer_id,
                         handler: NotifyHandler::Any,
                         event: Input::DiscoverResponse {
-                            registrations: vec![],
+                            registrations,
+                            cookie,
                         },
-                    })
-            }
-            Message::DiscoverResponse { registrations } => {
-                self.events
-                    .push_back(NetworkBehaviourAction::GenerateEvent(Event::Discovered {
-                        rendezvous_node: peer_id,
-                        ns: registrations,
-                    }))
+                    });
             }
+            Message::DiscoverResponse {
+                registrations,
+                cookie,
+            } => self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+                Event::Discovered {
+                    rendezvous_node: peer_id,
+                    registrations,
+                    cookie,
+                },
+            )),
             Message::FailedToDiscover { error } => self.events.push_back(
                 NetworkBehaviourAction::GenerateEvent(Event::FailedToDiscover {
                     rendezvous_node: peer_id,
@@ -230,8 +668,8 @@ impl NetworkBehaviour for Rendezvous {
 
     fn poll(
         &mut self,
-        _cx: &mut Context<'_>,
-        _: &mut impl PollParameters,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
     ) -> Poll<
         NetworkBehaviourAction<
             <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
@@ -242,6 +680,360 @@ impl NetworkBehaviour for Rendezvous {
             return Poll::Ready(event);
         }
 
+        self.sync_external_addresses(params);
+        self.poll_expiries(cx);
+
+        if self.auto_refresh {
+            self.poll_refresh(cx);
+        }
+
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(event);
+        }
+
         Poll::Pending
     }
 }
+
+impl Rendezvous {
+    /// Whether `peer_id` is one we keep a connection to ourselves, either because it's a
+    /// configured rendezvous node or because we called [`Rendezvous::register`] for it with
+    /// auto-refresh enabled (registrations made without auto-refresh are not tracked), as
+    /// opposed to an incidental peer we happen to be connected to for some other reason.
+    fn is_managed_rendezvous_node(&self, peer_id: &PeerId) -> bool {
+        self.rendezvous_nodes.contains_key(peer_id)
+            || self
+                .outgoing_registrations
+                .keys()
+                .any(|(_, node)| node == peer_id)
+    }
+
+    /// Folds any external address the swarm has confirmed (e.g. via `Identify`/AutoNAT) into
+    /// `external_addresses`, so the next auto-built peer record advertises it too.
+    fn sync_external_addresses(&mut self, params: &impl PollParameters) {
+        for record in params.external_addresses() {
+            if !self.external_addresses.contains(&record.addr) {
+                self.external_addresses.push(record.addr);
+            }
+        }
+    }
+
+    /// Pops the namespace of the oldest in-flight `RegisterRequest` we sent to `rendezvous_node`
+    /// on `connection`, i.e. the one the next `SuccessfullyRegistered`/`FailedToRegister` from it
+    /// answers. Falls back to an empty namespace for a response we can't account for.
+    fn next_pending_registration_namespace(
+        &mut self,
+        rendezvous_node: &PeerId,
+        connection: ConnectionId,
+    ) -> String {
+        self.pending_registrations
+            .get_mut(&(*rendezvous_node, connection))
+            .and_then(|pending| pending.pop_front())
+            .unwrap_or_default()
+    }
+
+    /// Re-sends a `RegisterRequest` for every namespace we previously registered with
+    /// `rendezvous_node`, e.g. right after reconnecting to it.
+    fn reregister_all(&mut self, rendezvous_node: &PeerId) {
+        let now = Instant::now();
+        let due: Vec<(String, i64, AuthenticatedPeerRecord)> = self
+            .outgoing_registrations
+            .iter_mut()
+            .filter(|((_, node), _)| node == rendezvous_node)
+            .map(|((ns, _), outgoing)| {
+                // Otherwise the stale `refresh_at` we resent this registration with is still due,
+                // and the very next `poll_refresh` fires a duplicate `RegisterRequest` for it.
+                outgoing.refresh_at = now + Duration::from_secs((outgoing.ttl / 2) as u64);
+                (ns.clone(), outgoing.ttl, outgoing.record.clone())
+            })
+            .collect();
+
+        for (ns, ttl, record) in due {
+            self.send_register_request(ns, *rendezvous_node, Some(ttl), record);
+        }
+    }
+
+    /// Re-sends every registration whose `refresh_at` deadline has passed, provided we currently
+    /// have a live connection to its rendezvous node.
+    fn poll_refresh(&mut self, cx: &mut Context<'_>) {
+        if let Some(timer) = &mut self.refresh_timer {
+            let _ = Pin::new(timer).poll(cx);
+        }
+
+        let now = Instant::now();
+        let due: Vec<(String, PeerId)> = self
+            .outgoing_registrations
+            .iter()
+            .filter(|(_, outgoing)| outgoing.refresh_at <= now)
+            .filter(|((_, node), _)| self.connection_states.get(node) == Some(&ConnectionState::Connected))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for (ns, rendezvous_node) in due {
+            let outgoing = self
+                .outgoing_registrations
+                .get_mut(&(ns.clone(), rendezvous_node))
+                .expect("key came from this map");
+
+            outgoing.refresh_at = now + Duration::from_secs((outgoing.ttl / 2) as u64);
+            let (ttl, record) = (outgoing.ttl, outgoing.record.clone());
+
+            self.send_register_request(ns, rendezvous_node, Some(ttl), record);
+        }
+
+        self.refresh_timer = match self
+            .outgoing_registrations
+            .values()
+            .map(|outgoing| outgoing.refresh_at)
+            .min()
+        {
+            Some(at) => {
+                let mut timer = Delay::new(at.saturating_duration_since(Instant::now()));
+                let _ = Pin::new(&mut timer).poll(cx);
+                Some(timer)
+            }
+            None => None,
+        };
+    }
+
+    /// Drops every registration whose TTL has elapsed and emits a
+    /// [`Event::RegistrationExpired`] for it, then re-arms `expiry_timer` for the next soonest
+    /// one so `poll` is woken again without being called in a busy loop.
+    fn poll_expiries(&mut self, cx: &mut Context<'_>) {
+        if let Some(timer) = &mut self.expiry_timer {
+            let _ = Pin::new(timer).poll(cx);
+        }
+
+        while let Some(Reverse((at, _, _))) = self.expiry_queue.peek() {
+            if *at > Instant::now() {
+                break;
+            }
+
+            let Reverse((at, ns, peer_id)) = self.expiry_queue.pop().expect("just peeked");
+
+            // A later registration may have bumped the TTL after this entry was queued; only
+            // act if this is still the current expiry for that (namespace, peer).
+            if self.expiries.get(&(ns.clone(), peer_id)) != Some(&at) {
+                continue;
+            }
+            self.expiries.remove(&(ns.clone(), peer_id));
+            self.registration_seq.remove(&(ns.clone(), peer_id));
+
+            if let Some(registrations) = self.registrations.get_mut(&ns) {
+                registrations.remove(&peer_id);
+            }
+
+            self.events
+                .push_back(NetworkBehaviourAction::GenerateEvent(
+                    Event::RegistrationExpired { peer_id, ns },
+                ));
+        }
+
+        self.expiry_timer = match self.expiry_queue.peek() {
+            Some(Reverse((at, _, _))) => {
+                let mut timer = Delay::new(at.saturating_duration_since(Instant::now()));
+                let _ = Pin::new(&mut timer).poll(cx);
+                Some(timer)
+            }
+            None => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A waker that does nothing; `poll_expiries` only needs *a* `Context` to poll its `Delay`s,
+    // it doesn't rely on ever being woken up for these tests.
+    fn noop_waker() -> std::task::Waker {
+        fn clone(_: *const ()) -> std::task::RawWaker {
+            raw_waker()
+        }
+        fn no_op(_: *const ()) {}
+        fn raw_waker() -> std::task::RawWaker {
+            static VTABLE: std::task::RawWakerVTable =
+                std::task::RawWakerVTable::new(clone, no_op, no_op, no_op);
+            std::task::RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        unsafe { std::task::Waker::from_raw(raw_waker()) }
+    }
+
+    fn test_record() -> AuthenticatedPeerRecord {
+        let identity = Keypair::generate_ed25519();
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/1234".parse().unwrap();
+        let record = PeerRecord::new(&identity, vec![addr]).unwrap();
+
+        AuthenticatedPeerRecord::from_record(&identity, record).unwrap()
+    }
+
+    #[test]
+    fn register_before_connecting_still_correlates_the_response_once_connected() {
+        let mut rendezvous = Rendezvous::new();
+        let node = PeerId::random();
+
+        rendezvous.add_rendezvous_node(node, "/ip4/127.0.0.1/tcp/1234".parse().unwrap());
+        // The `DialPeer` queued by `add_rendezvous_node` isn't what this test is about.
+        rendezvous.events.clear();
+
+        rendezvous.register("ns".to_string(), node, Some(test_record()));
+
+        let connection = ConnectionId::new(1);
+        rendezvous.inject_connection_established(
+            &node,
+            &connection,
+            &ConnectedPoint::Dialer {
+                address: "/ip4/127.0.0.1/tcp/1234".parse().unwrap(),
+            },
+        );
+
+        assert_eq!(
+            rendezvous.next_pending_registration_namespace(&node, connection),
+            "ns",
+            "the namespace queued before we knew which connection to use must survive \
+             connection establishment instead of coming back empty"
+        );
+    }
+
+    #[test]
+    fn failed_dial_to_a_managed_node_is_retried() {
+        let mut rendezvous = Rendezvous::new();
+        let node = PeerId::random();
+
+        rendezvous.add_rendezvous_node(node, "/ip4/127.0.0.1/tcp/1234".parse().unwrap());
+        rendezvous.events.clear(); // the initial `DialPeer` from `add_rendezvous_node`
+
+        rendezvous.inject_dial_failure(&node);
+
+        assert!(
+            matches!(
+                rendezvous.events.pop_front(),
+                Some(NetworkBehaviourAction::DialPeer { peer_id, .. }) if peer_id == node
+            ),
+            "a failed dial to a managed node must queue another attempt, not give up"
+        );
+    }
+
+    #[test]
+    fn failed_dial_to_an_unmanaged_peer_is_not_retried() {
+        let mut rendezvous = Rendezvous::new();
+        let peer = PeerId::random();
+
+        rendezvous.inject_dial_failure(&peer);
+
+        assert!(rendezvous.events.is_empty());
+    }
+
+    #[test]
+    fn cookie_round_trips_namespace_and_sequence_number() {
+        let cookie = encode_cookie("some-namespace", 42);
+
+        assert_eq!(decode_cookie(&cookie), Some(("some-namespace".to_string(), 42)));
+    }
+
+    #[test]
+    fn decode_cookie_rejects_truncated_input() {
+        assert_eq!(decode_cookie(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn paginate_encodes_cookie_from_the_last_returned_seq_not_the_namespace_total() {
+        let candidates = vec![(1, "a"), (2, "b"), (3, "c")];
+
+        let (returned, max_seq) = paginate(candidates, Some(2), 0);
+
+        assert_eq!(returned, vec!["a", "b"]);
+        assert_eq!(
+            max_seq, 2,
+            "the cookie must reflect the last seq actually returned, not seq 3 which \
+             `limit` truncated away"
+        );
+    }
+
+    #[test]
+    fn paginate_falls_back_to_since_when_nothing_matches() {
+        let candidates: Vec<(u64, &str)> = vec![];
+
+        let (returned, max_seq) = paginate(candidates, Some(10), 7);
+
+        assert!(returned.is_empty());
+        assert_eq!(max_seq, 7);
+    }
+
+    #[test]
+    fn paginating_repeatedly_with_the_returned_cookie_eventually_returns_everything() {
+        let total = 25;
+        let limit = Some(10);
+        let all: Vec<(u64, u64)> = (1..=total).map(|seq| (seq, seq)).collect();
+
+        let mut since = 0;
+        let mut seen = Vec::new();
+
+        loop {
+            let remaining: Vec<(u64, u64)> =
+                all.iter().copied().filter(|(seq, _)| *seq > since).collect();
+            if remaining.is_empty() {
+                break;
+            }
+
+            let (returned, max_seq) = paginate(remaining, limit, since);
+            assert_ne!(max_seq, since, "each call must make forward progress");
+
+            seen.extend(returned);
+            since = max_seq;
+        }
+
+        assert_eq!(seen, (1..=total).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn register_request_above_max_ttl_is_rejected() {
+        let rendezvous = Rendezvous::with_max_ttl(7200);
+
+        assert!(!rendezvous.exceeds_max_ttl(7200));
+        assert!(rendezvous.exceeds_max_ttl(7201));
+    }
+
+    #[test]
+    fn poll_expiries_emits_event_only_for_elapsed_entries_and_rearms_timer() {
+        let mut rendezvous = Rendezvous::new();
+        let expired_peer = PeerId::random();
+        let live_peer = PeerId::random();
+
+        let now = Instant::now();
+        let elapsed = now - Duration::from_secs(1);
+        let not_yet_due = now + Duration::from_secs(3600);
+
+        rendezvous.expiries.insert(("expired-ns".to_string(), expired_peer), elapsed);
+        rendezvous.expiry_queue.push(Reverse((elapsed, "expired-ns".to_string(), expired_peer)));
+        rendezvous.expiries.insert(("live-ns".to_string(), live_peer), not_yet_due);
+        rendezvous.expiry_queue.push(Reverse((not_yet_due, "live-ns".to_string(), live_peer)));
+
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        rendezvous.poll_expiries(&mut cx);
+
+        match rendezvous.events.pop_front() {
+            Some(NetworkBehaviourAction::GenerateEvent(Event::RegistrationExpired {
+                peer_id,
+                ns,
+            })) => {
+                assert_eq!(peer_id, expired_peer);
+                assert_eq!(ns, "expired-ns");
+            }
+            _ => panic!("expected a RegistrationExpired event"),
+        }
+        assert!(
+            rendezvous.events.is_empty(),
+            "the not-yet-due entry must not expire early"
+        );
+        assert!(
+            !rendezvous.expiries.contains_key(&("expired-ns".to_string(), expired_peer)),
+            "the expired entry must be cleared"
+        );
+        assert!(
+            rendezvous.expiry_timer.is_some(),
+            "the timer must be rearmed for the remaining, not-yet-due entry"
+        );
+    }
+}